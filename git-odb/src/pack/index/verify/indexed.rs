@@ -1,14 +1,33 @@
 use super::{Error, Mode, Outcome};
-use crate::{pack, pack::index};
-use git_features::progress::{self, Progress};
-use std::time::SystemTime;
+use crate::{
+    pack,
+    pack::{data::decode, graph, index},
+};
+use git_features::{
+    parallel::{self, in_parallel_if},
+    progress::{self, Progress},
+};
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Mutex,
+    time::SystemTime,
+};
 
 impl index::File {
+    /// Verify the pack by walking the resolved delta forest top-down, reconstructing each object through
+    /// [`decode_entry`](pack::data::File::decode_entry) so that the per-worker `cache` keeps an already
+    /// decoded base warm for all of its children.
+    ///
+    /// Note that the performance advantage of this mode over the lookup-based verifier rests entirely on
+    /// `make_cache` returning a *real* cache: with a [`cache::Never`](pack::cache::Never) every node
+    /// re-walks its delta chain to the root, so indexed lookup degrades to the lookup verifier's cost while
+    /// still paying for the resolved-offset tree. Callers wanting the speedup must hand us a caching
+    /// implementation.
     pub(crate) fn inner_verify_with_indexed_lookup<P, C>(
         &self,
-        _thread_limit: Option<usize>,
-        _mode: Mode,
-        _make_cache: impl Fn() -> C + Send + Sync,
+        thread_limit: Option<usize>,
+        mode: Mode,
+        make_cache: impl Fn() -> C + Send + Sync,
         mut progress: progress::DoOrDiscard<P>,
         pack: &pack::data::File,
     ) -> Result<Outcome, Error>
@@ -17,21 +36,243 @@ impl index::File {
         <P as Progress>::SubProgress: Send,
         C: pack::cache::DecodeEntry,
     {
-        let offsets = {
+        let tree = {
             let mut indexing_progress = progress.add_child("preparing pack offsets");
             indexing_progress.init(Some(self.num_objects), Some("objects"));
             let then = SystemTime::now();
-            let iter = self.sorted_offsets().into_iter();
+            let tree = graph::DeltaTree::from_sorted_offsets(
+                self.sorted_offsets().into_iter(),
+                pack.path(),
+                progress.add_child("indexing"),
+            )?;
             let elapsed = then.elapsed().expect("system time").as_secs_f32();
             indexing_progress.info(format!(
                 "in {:.02}s ({} objects/s)",
                 elapsed,
                 self.num_objects as f32 / elapsed
             ));
-            iter
+            tree
         };
-        pack::graph::DeltaTree::from_sorted_offsets(offsets, pack.path(), progress.add_child("indexing"))?;
 
-        unimplemented!()
+        // Each base object roots a subtree of deltas that depend (transitively) on it. Independent
+        // subtrees share nothing, so we can hand one root each to a worker and walk them top-down.
+        let roots: Vec<_> = tree.bases().collect();
+        let (_, thread_limit, _) =
+            parallel::optimize_chunk_size_and_thread_limit(1, Some(roots.len()), thread_limit, None);
+
+        let reduce_progress = Mutex::new({
+            let mut p = progress.add_child("verifying");
+            p.init(Some(self.num_objects), Some("objects"));
+            p
+        });
+        let state_per_thread = |index| {
+            (
+                make_cache(),
+                // reconstruction buffer reused across objects; each decode walks the delta chain through
+                // the decode cache, so a base is decompressed at most once per worker as long as that cache
+                // keeps it (with `cache::Never`, every object re-walks its chain).
+                Vec::<u8>::with_capacity(2048),
+                reduce_progress.lock().expect("not poisoned").add_child(format!("thread {}", index)),
+            )
+        };
+
+        in_parallel_if(
+            // Gate on the total number of objects rather than the number of roots: a typical pack has few
+            // base objects but many deltas, and that work is what parallelism needs to cover.
+            || self.num_objects > 1_000,
+            roots.into_iter(),
+            thread_limit,
+            state_per_thread,
+            |root: graph::Node, (cache, buf, progress)| {
+                self.verify_subtree(&tree, root, pack, mode, cache, buf, progress)
+            },
+            Reducer::from_progress(&reduce_progress, pack.data_len() as u64),
+        )
+    }
+
+    /// Walk the delta-subtree rooted at `root` top-down, reconstructing every object exactly once and
+    /// checking it against the [`ObjectId`](git_object::owned::Id) recorded at its index offset.
+    ///
+    /// Decoding runs through [`pack::data::File::decode_entry`], so `cache` keeps already-decoded bases
+    /// warm: because we descend parents before their children, a child's base is served from the cache
+    /// instead of re-walking the chain to its root. An OFS/REF delta whose base lives outside the pack
+    /// (a thin pack) cannot be resolved here and surfaces as a [`decode::Error::DeltaBaseUnresolved`].
+    #[allow(clippy::too_many_arguments)]
+    fn verify_subtree<C>(
+        &self,
+        tree: &graph::DeltaTree,
+        root: graph::Node,
+        pack: &pack::data::File,
+        mode: Mode,
+        cache: &mut C,
+        buf: &mut Vec<u8>,
+        progress: &mut impl Progress,
+    ) -> Result<Vec<decode::Outcome>, Error>
+    where
+        C: pack::cache::DecodeEntry,
+    {
+        let mut outcomes = Vec::new();
+        let mut children = Vec::new();
+        let mut stack = vec![root];
+        // A malformed or adversarial pack can contain a back-edge (e.g. an in-pack REF_DELTA base that
+        // transitively deltas back onto a descendant), which would otherwise make `stack` grow without
+        // bound. Track the entry offsets we've already reconstructed and refuse to revisit one.
+        let mut seen = HashSet::new();
+        while let Some(node) = stack.pop() {
+            if !seen.insert(node.entry_index) {
+                return Err(Error::Graph(graph::Error::Cycle(node.pack_offset)));
+            }
+            let outcome = pack
+                .decode_entry(
+                    pack.entry(node.pack_offset),
+                    buf,
+                    // REF deltas built without `pack.deltaBaseOffset` carry their base id rather than an
+                    // offset; resolve it through our index so intra-pack bases decode normally. A `None`
+                    // return lets `decode_entry` report the genuinely-missing (thin-pack) base.
+                    |base_id, _| {
+                        self.lookup(base_id)
+                            .map(|idx| decode::ResolvedBase::InPack(pack.entry(self.pack_offset_at_index(idx))))
+                    },
+                    cache,
+                )
+                .map_err(|source| Error::PackDecode {
+                    id: self.oid_at_index(node.entry_index).to_owned(),
+                    offset: node.pack_offset,
+                    source,
+                })?;
+
+            self.assert_hash_matches(node, outcome.kind, buf)?;
+            if mode.crc32() {
+                self.assert_crc32_matches(node, pack)?;
+            }
+
+            outcomes.push(outcome);
+            progress.inc();
+
+            children.clear();
+            tree.children(node, &mut children);
+            stack.extend(children.iter().copied());
+        }
+        Ok(outcomes)
+    }
+
+    fn assert_hash_matches(&self, node: graph::Node, kind: git_object::Kind, data: &[u8]) -> Result<(), Error> {
+        let expected = self.oid_at_index(node.entry_index);
+        let mut hasher = git_features::hash::hasher(expected.kind());
+        hasher.update(&object_header(kind, data.len()));
+        hasher.update(data);
+        let actual = git_object::owned::Id::from(hasher.digest());
+        if actual.to_borrowed() != expected {
+            return Err(Error::PackDecode {
+                id: expected.to_owned(),
+                offset: node.pack_offset,
+                source: decode::Error::ObjectDecodeMismatch { expected: expected.to_owned(), actual },
+            });
+        }
+        Ok(())
+    }
+
+    fn assert_crc32_matches(&self, node: graph::Node, pack: &pack::data::File) -> Result<(), Error> {
+        let expected = self
+            .crc32_at_index(node.entry_index)
+            .expect("CRC32 is always present in v2 indices when requested");
+        let actual = pack.entry_crc32(node.pack_offset);
+        if actual != expected {
+            return Err(Error::Crc32Mismatch {
+                expected,
+                actual,
+                offset: node.pack_offset,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Build the loose-object header (`"<kind> <size>\0"`) that prefixes the object's data when hashing.
+fn object_header(kind: git_object::Kind, size: usize) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(16);
+    buf.extend_from_slice(kind.to_bytes());
+    buf.push(b' ');
+    buf.extend_from_slice(size.to_string().as_bytes());
+    buf.push(0);
+    buf
+}
+
+/// Fold per-object decode statistics into the module's [`Outcome`] as they arrive.
+///
+/// Statistics are accumulated as each subtree's outcomes arrive, so the reducer keeps no per-object
+/// [`decode::Outcome`] of its own. Each worker still holds one outcome per object of the subtree it is verifying.
+struct Reducer<'a, P> {
+    progress: &'a Mutex<P>,
+    pack_size: u64,
+    count: usize,
+    objects_per_chain_length: BTreeMap<u32, u32>,
+    total_compressed_entries_size: u64,
+    total_decompressed_entries_size: u64,
+    total_object_size: u64,
+    average: decode::Outcome,
+}
+
+impl<'a, P> Reducer<'a, P>
+where
+    P: Progress,
+{
+    fn from_progress(progress: &'a Mutex<P>, pack_size: u64) -> Self {
+        Reducer {
+            progress,
+            pack_size,
+            count: 0,
+            objects_per_chain_length: BTreeMap::new(),
+            total_compressed_entries_size: 0,
+            total_decompressed_entries_size: 0,
+            total_object_size: 0,
+            average: decode::Outcome::default_from_kind(git_object::Kind::Tree),
+        }
+    }
+}
+
+impl<'a, P> parallel::Reducer for Reducer<'a, P>
+where
+    P: Progress,
+{
+    type Input = Result<Vec<decode::Outcome>, Error>;
+    type FeedProduce = ();
+    type Output = Outcome;
+    type Error = Error;
+
+    fn feed(&mut self, input: Self::Input) -> Result<Self::FeedProduce, Self::Error> {
+        let outcomes = input?;
+        self.count += outcomes.len();
+        for o in &outcomes {
+            *self.objects_per_chain_length.entry(o.num_deltas).or_insert(0) += 1;
+            self.total_compressed_entries_size += o.compressed_size as u64;
+            self.total_decompressed_entries_size += o.decompressed_size;
+            self.total_object_size += o.object_size;
+            self.average.num_deltas += o.num_deltas;
+            self.average.decompressed_size += o.decompressed_size;
+            self.average.compressed_size += o.compressed_size;
+            self.average.object_size += o.object_size;
+        }
+        // Report the number of verified objects; the progress renderer derives objects/s from the unit.
+        self.progress.lock().expect("not poisoned").set(self.count);
+        Ok(())
+    }
+
+    fn finalize(self) -> Result<Self::Output, Self::Error> {
+        let count = self.count.max(1) as u64;
+        let mut average = self.average;
+        average.num_deltas = (average.num_deltas as u64 / count) as u32;
+        average.decompressed_size /= count;
+        average.compressed_size = (average.compressed_size as u64 / count) as usize;
+        average.object_size /= count;
+
+        Ok(Outcome {
+            average,
+            objects_per_chain_length: self.objects_per_chain_length,
+            total_compressed_entries_size: self.total_compressed_entries_size,
+            total_decompressed_entries_size: self.total_decompressed_entries_size,
+            total_object_size: self.total_object_size,
+            pack_size: self.pack_size,
+        })
     }
 }
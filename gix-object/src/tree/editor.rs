@@ -2,8 +2,8 @@ use crate::tree::EntryKind;
 use crate::{tree, Tree};
 use bstr::{BStr, BString, ByteSlice, ByteVec};
 use gix_hash::ObjectId;
-use gix_hashtable::hash_map::Entry;
 use std::cmp::Ordering;
+use std::collections::hash_map::Entry;
 
 /// The state needed to apply edits instantly to in-memory trees.
 ///
@@ -12,24 +12,31 @@ use std::cmp::Ordering;
 ///
 /// The editor is optimized to edit existing trees, but can deal with building entirely new trees as well
 /// with some penalties.
-///
-/// ### Note
-///
-/// For reasons of efficiency, internally a SHA1 based hashmap is used to avoid having to store full paths
-/// to each edited tree. The chance of collision is low, but could be engineered to overwrite or write into
-/// an unintended tree.
 #[doc(alias = "TreeUpdateBuilder", alias = "git2")]
 pub struct Editor<'a> {
     /// A way to lookup trees.
     find: &'a dyn crate::FindExt,
-    /// All trees we currently hold in memory. Each of these may change while adding and removing entries.
-    /// null-object-ids mark tree-entries whose value we don't know yet, they are placeholders that will be
-    /// dropped when writing at the latest.
-    trees: gix_hashtable::HashMap<ObjectId, Tree>,
-    /// A buffer to build up paths when finding the tree to edit.
-    path_buf: BString,
+    /// The root of the in-memory tree of nodes we are editing. Each node owns the [`Tree`] at its level
+    /// along with a map from path-component to the child node below it, so a tree is addressed by the
+    /// chain of basenames leading to it rather than by a hash over its full path.
+    root: Node,
     /// Our buffer for storing tree-data in, right before decoding it.
     tree_buf: Vec<u8>,
+    /// Maps the full path of a [renamed](Self::rename) entry to the full path its content originated from.
+    /// Entries are invalidated once their destination is overwritten or removed again.
+    copy_sources: std::collections::HashMap<BString, BString>,
+}
+
+/// A single level in the in-memory tree of edits: the [`Tree`] being edited here, keyed into by its
+/// basename from the parent, along with the child nodes below it.
+#[derive(Default)]
+struct Node {
+    /// The tree being edited at this level. It may change freely while adding and removing entries;
+    /// null-object-ids mark tree-entries whose value we don't know yet, they are placeholders that will be
+    /// dropped when writing at the latest.
+    tree: Tree,
+    /// The descendant trees we have loaded or created so far, keyed by their basename in `tree`.
+    children: std::collections::HashMap<BString, Node>,
 }
 
 /// Lifecycle
@@ -40,9 +47,12 @@ impl<'a> Editor<'a> {
     pub fn new(root: Tree, find: &'a dyn crate::FindExt) -> Self {
         Editor {
             find,
-            trees: gix_hashtable::HashMap::from_iter(Some((empty_path_hash(), root))),
-            path_buf: Vec::with_capacity(256).into(),
+            root: Node {
+                tree: root,
+                children: Default::default(),
+            },
             tree_buf: Vec::with_capacity(512),
+            copy_sources: Default::default(),
         }
     }
 }
@@ -60,64 +70,17 @@ impl<'a> Editor<'a> {
     /// Future calls to [`upsert`](Self::upsert) or similar will keep working on the last seen state of the
     /// just-written root-tree.
     /// If this is not desired, use [set_root()](Self::set_root()).
+    ///
+    /// Note that any sub-tree that became empty — including the ancestor directories left behind by
+    /// [`remove_matching`](Self::remove_matching) — is pruned here rather than written, so a directory whose
+    /// last entry was removed does not survive into the written tree.
     pub fn write<E>(&mut self, mut out: impl FnMut(&Tree) -> Result<ObjectId, E>) -> Result<ObjectId, E> {
-        assert_ne!(self.trees.len(), 0, "there is at least the root tree");
-
-        // back is for children, front is for parents.
-        let mut parents = vec![(
-            None::<usize>,
-            BString::default(),
-            self.trees
-                .remove(&empty_path_hash())
-                .expect("root tree is always present"),
-        )];
-        let mut children = Vec::new();
-        while let Some((parent_idx, mut rela_path, mut tree)) = children.pop().or_else(|| parents.pop()) {
-            let mut all_entries_unchanged_or_written = true;
-            for entry in &tree.entries {
-                if entry.mode.is_tree() {
-                    let prev_len = push_path_component(&mut rela_path, &entry.filename);
-                    if let Some(sub_tree) = self.trees.remove(&path_hash(&rela_path)) {
-                        all_entries_unchanged_or_written = false;
-                        let next_parent_idx = parents.len();
-                        children.push((Some(next_parent_idx), rela_path.clone(), sub_tree));
-                    }
-                    rela_path.truncate(prev_len);
-                }
-            }
-            if all_entries_unchanged_or_written {
-                tree.entries.retain(|e| !e.oid.is_null());
-                if let Some((_, _, parent_to_adjust)) =
-                    parent_idx.map(|idx| parents.get_mut(idx).expect("always present, pointing towards zero"))
-                {
-                    let name = filename(rela_path.as_bstr());
-                    let entry_idx = parent_to_adjust
-                        .entries
-                        .binary_search_by(|e| cmp_entry_with_name(e, name, true))
-                        .expect("the parent always knows us by name");
-                    if tree.entries.is_empty() {
-                        parent_to_adjust.entries.remove(entry_idx);
-                    } else {
-                        parent_to_adjust.entries[entry_idx].oid = out(&tree)?;
-                    }
-                } else if parents.is_empty() {
-                    debug_assert!(children.is_empty(), "we consume children before parents");
-                    debug_assert!(rela_path.is_empty(), "this should always be the root tree");
-
-                    // There may be left-over trees if they are replaced with blobs for example.
-                    let root_tree_id = out(&tree)?;
-                    self.trees.clear();
-                    self.trees.insert(empty_path_hash(), tree);
-                    return Ok(root_tree_id);
-                } else if !tree.entries.is_empty() {
-                    out(&tree)?;
-                }
-            } else {
-                parents.push((parent_idx, rela_path, tree));
-            }
-        }
-
-        unreachable!("we exit as soon as everything is consumed")
+        write_node(&mut self.root, &mut out)?;
+        let root_tree_id = out(&self.root.tree)?;
+        // The children have all been written and folded into the root tree; start fresh from it so future
+        // edits reload sub-trees from the object database as needed.
+        self.root.children.clear();
+        Ok(root_tree_id)
     }
 
     /// Remove the entry at `rela_path`, loading all trees on the path accordingly.
@@ -127,7 +90,25 @@ impl<'a> Editor<'a> {
         I: IntoIterator<Item = C>,
         C: AsRef<BStr>,
     {
-        self.upsert_or_remove(rela_path, None)
+        self.upsert_or_remove(rela_path, None, None)
+    }
+
+    /// Like [`remove`](Self::remove), but call `each_ancestor` for each tree on the way down to `rela_path`,
+    /// from the root towards the leaf, passing the relative path of that tree and a mutable reference to it.
+    ///
+    /// This is useful to maintain aggregate state up the path in a single traversal, for instance a per-directory
+    /// tally of changed entries or a propagated *dirty* flag, without re-walking the tree afterwards.
+    pub fn remove_with<I, C>(
+        &mut self,
+        rela_path: I,
+        each_ancestor: impl FnMut(&BStr, &mut Tree),
+    ) -> Result<&mut Self, crate::find::existing_object::Error>
+    where
+        I: IntoIterator<Item = C>,
+        C: AsRef<BStr>,
+    {
+        let mut each_ancestor = each_ancestor;
+        self.upsert_or_remove(rela_path, None, Some(&mut each_ancestor))
     }
 
     /// Insert a new entry of `kind` with `id` at `rela_path`, an iterator over each path component in the tree,
@@ -154,33 +135,64 @@ impl<'a> Editor<'a> {
         I: IntoIterator<Item = C>,
         C: AsRef<BStr>,
     {
-        self.upsert_or_remove(rela_path, Some((kind, id)))
+        self.upsert_or_remove(rela_path, Some((kind, id)), None)
+    }
+
+    /// Like [`upsert`](Self::upsert), but call `each_ancestor` for each tree on the way down to `rela_path`,
+    /// from the root towards the leaf, passing the relative path of that tree and a mutable reference to it.
+    ///
+    /// This is useful to maintain aggregate state up the path in a single traversal, for instance a per-directory
+    /// tally of changed entries or a propagated *dirty* flag, without re-walking the tree afterwards.
+    pub fn upsert_with<I, C>(
+        &mut self,
+        rela_path: I,
+        kind: EntryKind,
+        id: ObjectId,
+        each_ancestor: impl FnMut(&BStr, &mut Tree),
+    ) -> Result<&mut Self, crate::find::existing_object::Error>
+    where
+        I: IntoIterator<Item = C>,
+        C: AsRef<BStr>,
+    {
+        let mut each_ancestor = each_ancestor;
+        self.upsert_or_remove(rela_path, Some((kind, id)), Some(&mut each_ancestor))
     }
 
     fn upsert_or_remove<I, C>(
         &mut self,
         rela_path: I,
         kind_and_id: Option<(EntryKind, ObjectId)>,
+        mut each_ancestor: Option<&mut dyn FnMut(&BStr, &mut Tree)>,
     ) -> Result<&mut Self, crate::find::existing_object::Error>
     where
         I: IntoIterator<Item = C>,
         C: AsRef<BStr>,
     {
-        let mut cursor = self.trees.get_mut(&empty_path_hash()).expect("root is always present");
-        self.path_buf.clear();
+        let track_path = !self.copy_sources.is_empty();
+        let mut edited_path = BString::default();
+        let mut cursor = &mut self.root;
         let mut rela_path = rela_path.into_iter().peekable();
+        let mut ancestor_path = BString::default();
         let new_kind_is_tree = kind_and_id.map_or(false, |(kind, _)| kind == EntryKind::Tree);
         while let Some(name) = rela_path.next() {
             let name = name.as_ref();
             let is_last = rela_path.peek().is_none();
+            if track_path {
+                push_path_component(&mut edited_path, name);
+            }
+            if let Some(each_ancestor) = each_ancestor.as_deref_mut() {
+                each_ancestor(ancestor_path.as_bstr(), &mut cursor.tree);
+            }
             let mut needs_sorting = false;
             let current_level_must_be_tree = !is_last || new_kind_is_tree;
             let check_type_change = |entry: &tree::Entry| entry.mode.is_tree() != current_level_must_be_tree;
             let tree_to_lookup = match cursor
+                .tree
                 .entries
                 .binary_search_by(|e| cmp_entry_with_name(e, name, false))
                 .or_else(|file_insertion_idx| {
                     cursor
+                        .tree
                         .entries
                         .binary_search_by(|e| cmp_entry_with_name(e, name, true))
                         .map_err(|dir_insertion_index| {
@@ -195,10 +207,11 @@ impl<'a> Editor<'a> {
                     match kind_and_id {
                         None => {
                             if is_last {
-                                cursor.entries.remove(idx);
+                                cursor.tree.entries.remove(idx);
+                                cursor.children.remove(name.as_bstr());
                                 break;
                             } else {
-                                let entry = &cursor.entries[idx];
+                                let entry = &cursor.tree.entries[idx];
                                 if entry.mode.is_tree() {
                                     Some(entry.oid)
                                 } else {
@@ -207,18 +220,23 @@ impl<'a> Editor<'a> {
                             }
                         }
                         Some((kind, id)) => {
-                            let entry = &mut cursor.entries[idx];
+                            let entry = &mut cursor.tree.entries[idx];
                             if is_last {
                                 // unconditionally overwrite what's there.
                                 entry.oid = id;
                                 needs_sorting = check_type_change(entry);
                                 entry.mode = kind.into();
+                                // A leaf overwrites whatever sub-tree used to live here.
+                                cursor.children.remove(name.as_bstr());
                                 None
                             } else if entry.mode.is_tree() {
                                 // Possibly lookup the existing tree on our way down the path.
                                 Some(entry.oid)
                             } else {
                                 // it is no tree, but we are traversing a path, so turn it into one.
+                                if track_path {
+                                    self.copy_sources.remove(edited_path.as_bstr());
+                                }
                                 entry.oid = id.kind().null();
                                 needs_sorting = check_type_change(entry);
                                 entry.mode = EntryKind::Tree.into();
@@ -230,7 +248,7 @@ impl<'a> Editor<'a> {
                 Err(insertion_idx) => match kind_and_id {
                     None => break,
                     Some((kind, id)) => {
-                        cursor.entries.insert(
+                        cursor.tree.entries.insert(
                             insertion_idx,
                             tree::Entry {
                                 filename: name.into(),
@@ -246,24 +264,169 @@ impl<'a> Editor<'a> {
                 },
             };
             if needs_sorting {
-                cursor.entries.sort();
+                cursor.tree.entries.sort();
             }
             if is_last {
                 break;
             }
-            push_path_component(&mut self.path_buf, name);
-            let path_id = path_hash(&self.path_buf);
-            cursor = match self.trees.entry(path_id) {
+            if each_ancestor.is_some() {
+                push_path_component(&mut ancestor_path, name);
+            }
+            cursor = match cursor.children.entry(name.into()) {
                 Entry::Occupied(e) => e.into_mut(),
-                Entry::Vacant(e) => e.insert(
-                    if let Some(tree_id) = tree_to_lookup.filter(|tree_id| !tree_id.is_empty_tree()) {
+                Entry::Vacant(e) => e.insert(Node {
+                    tree: if let Some(tree_id) = tree_to_lookup.filter(|tree_id| !tree_id.is_empty_tree()) {
                         self.find.find_tree(&tree_id, &mut self.tree_buf)?.into()
                     } else {
                         Tree::default()
                     },
-                ),
+                    children: Default::default(),
+                }),
             };
         }
+        // Overwriting or removing a tracked destination, or a tree containing one, drops the recorded provenance
+        // for it. A removal that stopped early at a non-tree touched no entry and drops nothing.
+        if track_path && rela_path.peek().is_none() {
+            self.copy_sources.retain(|dst, _| !is_same_or_below(dst, &edited_path));
+        }
+        Ok(self)
+    }
+
+    /// Move the entry at `from_rela_path` to `to_rela_path`, recording that the destination originated at the
+    /// source so that [`copy_source`](Self::copy_source) can report it later.
+    ///
+    /// It's no error if there is no entry at `from_rela_path`, in which case nothing is moved, but any previously
+    /// recorded provenance for `to_rela_path` is still dropped. Otherwise it is replaced.
+    pub fn rename<I, C, J, D>(
+        &mut self,
+        from_rela_path: I,
+        to_rela_path: J,
+    ) -> Result<&mut Self, crate::find::existing_object::Error>
+    where
+        I: IntoIterator<Item = C>,
+        C: AsRef<BStr>,
+        J: IntoIterator<Item = D>,
+        D: AsRef<BStr>,
+    {
+        let from: Vec<BString> = from_rela_path.into_iter().map(|c| c.as_ref().to_owned()).collect();
+        let to: Vec<BString> = to_rela_path.into_iter().map(|c| c.as_ref().to_owned()).collect();
+        let to_path = join_path(&to);
+        // A rename always replaces any provenance previously recorded for the destination - including when
+        // the source is missing and the move is a no-op. Invalidate it here explicitly rather than relying
+        // on the `upsert` below, which only does so when `copy_sources` was already non-empty.
+        self.copy_sources.remove(to_path.as_bstr());
+        let Some((kind, id)) = self.find_entry(&from)? else {
+            return Ok(self);
+        };
+        self.remove(from.iter())?;
+        self.upsert(to.iter(), kind, id)?;
+        self.copy_sources.insert(to_path, join_path(&from));
+        Ok(self)
+    }
+
+    /// Return the full path the content now at `rela_path` originated from, if it was produced by a tracked
+    /// [`rename`](Self::rename) that hasn't been invalidated since.
+    pub fn copy_source<I, C>(&self, rela_path: I) -> Option<&BStr>
+    where
+        I: IntoIterator<Item = C>,
+        C: AsRef<BStr>,
+    {
+        let path = join_path(rela_path);
+        self.copy_sources.get(path.as_bstr()).map(|source| source.as_bstr())
+    }
+
+    /// Remove and return all recorded rename provenance as `(destination, source)` pairs, typically after
+    /// [`write`](Self::write), so it can be handed to a downstream diff.
+    pub fn drain_copy_sources(&mut self) -> impl Iterator<Item = (BString, BString)> + '_ {
+        self.copy_sources.drain()
+    }
+
+    /// Look up the [kind](EntryKind) and [id](ObjectId) of the entry at `rela_path`, loading trees on the way
+    /// down, returning `None` if it doesn't exist.
+    fn find_entry(
+        &mut self,
+        rela_path: &[BString],
+    ) -> Result<Option<(EntryKind, ObjectId)>, crate::find::existing_object::Error> {
+        let Editor { find, root, tree_buf, .. } = self;
+        let mut cursor = &mut *root;
+        let mut components = rela_path.iter().peekable();
+        while let Some(name) = components.next() {
+            let is_last = components.peek().is_none();
+            let idx = match cursor
+                .tree
+                .entries
+                .binary_search_by(|e| cmp_entry_with_name(e, name.as_bstr(), false))
+                .or_else(|_| {
+                    cursor
+                        .tree
+                        .entries
+                        .binary_search_by(|e| cmp_entry_with_name(e, name.as_bstr(), true))
+                }) {
+                Ok(idx) => idx,
+                Err(_) => return Ok(None),
+            };
+            let entry = &cursor.tree.entries[idx];
+            if is_last {
+                return Ok(Some((entry.mode.kind(), entry.oid)));
+            }
+            if !entry.mode.is_tree() {
+                return Ok(None);
+            }
+            let tree_id = entry.oid;
+            cursor = match cursor.children.entry(name.clone()) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => e.insert(Node {
+                    tree: if !tree_id.is_null() && !tree_id.is_empty_tree() {
+                        find.find_tree(&tree_id, tree_buf)?.into()
+                    } else {
+                        Tree::default()
+                    },
+                    children: Default::default(),
+                }),
+            };
+        }
+        Ok(None)
+    }
+
+    /// Remove every entry whose full relative path and [kind](EntryKind) satisfy `matcher`, loading
+    /// intermediate trees on demand just like [`upsert`](Self::upsert) does.
+    ///
+    /// A matching tree is removed along with everything below it. Non-matching trees are descended into so
+    /// that matches nested arbitrarily deep are found. Sub-trees that become empty as a result are left in
+    /// place and pruned by the next [`write`](Self::write), so `remove_matching(|p, _| p.starts_with(b"target/"))`
+    /// drops the entire `target/` hierarchy in a single call.
+    pub fn remove_matching(
+        &mut self,
+        matcher: impl Fn(&BStr, EntryKind) -> bool,
+    ) -> Result<&mut Self, crate::find::existing_object::Error> {
+        let Editor {
+            find,
+            root,
+            tree_buf,
+            copy_sources,
+        } = self;
+        let mut path = BString::default();
+        // Each removed entry drops the provenance recorded at or below it.
+        let mut on_removed = |removed: &BStr, _: &tree::Entry| {
+            copy_sources.retain(|dst, _| !is_same_or_below(dst, removed));
+        };
+        walk_matching(root, &mut path, &matcher, true, &mut on_removed, *find, tree_buf)?;
+        Ok(self)
+    }
+
+    /// Call `for_each` for every entry whose full relative path and [kind](EntryKind) satisfy `matcher`,
+    /// without changing anything, loading intermediate trees on demand just like [`upsert`](Self::upsert) does.
+    ///
+    /// Unlike [`remove_matching`](Self::remove_matching), a matching tree is also descended into, so callers
+    /// observe matching entries at every level.
+    pub fn for_each_matching(
+        &mut self,
+        matcher: impl Fn(&BStr, EntryKind) -> bool,
+        mut for_each: impl FnMut(&BStr, &tree::Entry),
+    ) -> Result<&mut Self, crate::find::existing_object::Error> {
+        let Editor { find, root, tree_buf, .. } = self;
+        let mut path = BString::default();
+        walk_matching(root, &mut path, &matcher, false, &mut for_each, *find, tree_buf)?;
         Ok(self)
     }
 
@@ -273,41 +436,281 @@ impl<'a> Editor<'a> {
     ///
     /// This is useful if the same editor is re-used for various trees.
     pub fn set_root(&mut self, root: Tree) -> &mut Self {
-        self.trees.clear();
-        self.trees.insert(empty_path_hash(), root);
+        self.root = Node {
+            tree: root,
+            children: Default::default(),
+        };
+        self.copy_sources.clear();
         self
     }
 }
 
-fn cmp_entry_with_name(a: &tree::Entry, filename: &BStr, is_tree: bool) -> Ordering {
-    let common = a.filename.len().min(filename.len());
-    a.filename[..common].cmp(&filename[..common]).then_with(|| {
-        let a = a.filename.get(common).or_else(|| a.mode.is_tree().then_some(&b'/'));
-        let b = filename.get(common).or_else(|| is_tree.then_some(&b'/'));
-        a.cmp(&b)
-    })
+/// Join the path `components` into a single relative path, e.g. `["a", "b"]` into `a/b`.
+fn join_path(components: impl IntoIterator<Item = impl AsRef<BStr>>) -> BString {
+    let mut out = BString::default();
+    for component in components {
+        push_path_component(&mut out, component.as_ref());
+    }
+    out
 }
 
-fn filename(path: &BStr) -> &BStr {
-    path.rfind_byte(b'/').map_or(path, |pos| &path[pos + 1..])
+/// Return `true` if `path` is `ancestor` itself or lies below it, e.g. `a/b` is below `a`, but `ab` is not.
+fn is_same_or_below(path: &[u8], ancestor: &[u8]) -> bool {
+    path.starts_with(ancestor) && (path.len() == ancestor.len() || path[ancestor.len()] == b'/')
 }
 
-fn empty_path_hash() -> ObjectId {
-    gix_features::hash::hasher(gix_hash::Kind::Sha1).digest().into()
+/// Recurse into all loaded sub-trees of `node`, writing the changed ones via `out` and folding their object ids
+/// back into `node`'s entries. Sub-trees that became empty are pruned from the parent, and placeholder entries
+/// that were never filled in are dropped.
+fn write_node<F, E>(node: &mut Node, out: &mut F) -> Result<(), E>
+where
+    F: FnMut(&Tree) -> Result<ObjectId, E>,
+{
+    let mut idx = 0;
+    while idx < node.tree.entries.len() {
+        if node.tree.entries[idx].mode.is_tree() {
+            if let Some(mut child) = node.children.remove(node.tree.entries[idx].filename.as_bstr()) {
+                write_node(&mut child, out)?;
+                if child.tree.entries.is_empty() {
+                    node.tree.entries.remove(idx);
+                    continue;
+                }
+                node.tree.entries[idx].oid = out(&child.tree)?;
+            }
+        }
+        idx += 1;
+    }
+    node.tree.entries.retain(|e| !e.oid.is_null());
+    Ok(())
 }
 
-fn path_hash(path: &[u8]) -> ObjectId {
-    let mut hasher = gix_features::hash::hasher(gix_hash::Kind::Sha1);
-    hasher.update(path);
-    hasher.digest().into()
+/// Walk `node` and all of its (lazily loaded) descendants, applying `matcher` against each entry's full path.
+/// When `remove` is set, matching entries are deleted (matching trees wholesale) and passed to `for_each` right
+/// before; otherwise `for_each` observes them. Non-matching trees are always descended into so deeply nested matches are found.
+fn walk_matching(
+    node: &mut Node,
+    path: &mut BString,
+    matcher: &dyn Fn(&BStr, EntryKind) -> bool,
+    remove: bool,
+    for_each: &mut dyn FnMut(&BStr, &tree::Entry),
+    find: &dyn crate::FindExt,
+    tree_buf: &mut Vec<u8>,
+) -> Result<(), crate::find::existing_object::Error> {
+    let mut idx = 0;
+    while idx < node.tree.entries.len() {
+        let entry = &node.tree.entries[idx];
+        let prev_len = path.len();
+        push_path_component(path, &entry.filename);
+        let kind = entry.mode.kind();
+        let is_tree = entry.mode.is_tree();
+        let matched = matcher(path.as_bstr(), kind);
+
+        if matched && remove {
+            let name = entry.filename.clone();
+            for_each(path.as_bstr(), entry);
+            node.tree.entries.remove(idx);
+            node.children.remove(name.as_bstr());
+            // Removal keeps the remaining entries sorted, so no re-sorting is required.
+            path.truncate(prev_len);
+            continue;
+        }
+        if matched {
+            for_each(path.as_bstr(), &node.tree.entries[idx]);
+        }
+        if is_tree && !(matched && remove) {
+            let tree_id = node.tree.entries[idx].oid;
+            let name = node.tree.entries[idx].filename.clone();
+            let child = match node.children.entry(name) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => e.insert(Node {
+                    tree: if !tree_id.is_null() && !tree_id.is_empty_tree() {
+                        find.find_tree(&tree_id, tree_buf)?.into()
+                    } else {
+                        Tree::default()
+                    },
+                    children: Default::default(),
+                }),
+            };
+            walk_matching(child, path, matcher, remove, for_each, find, tree_buf)?;
+        }
+        path.truncate(prev_len);
+        idx += 1;
+    }
+    Ok(())
 }
 
-fn push_path_component(base: &mut BString, component: &[u8]) -> usize {
-    let prev_len = base.len();
+fn push_path_component(base: &mut BString, component: &[u8]) {
     debug_assert!(base.last() != Some(&b'/'));
     if !base.is_empty() {
         base.push_byte(b'/');
     }
     base.push_str(component);
-    prev_len
+}
+
+fn cmp_entry_with_name(a: &tree::Entry, filename: &BStr, is_tree: bool) -> Ordering {
+    let common = a.filename.len().min(filename.len());
+    a.filename[..common].cmp(&filename[..common]).then_with(|| {
+        let a = a.filename.get(common).or_else(|| a.mode.is_tree().then_some(&b'/'));
+        let b = filename.get(common).or_else(|| is_tree.then_some(&b'/'));
+        a.cmp(&b)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bstr::ByteSlice;
+
+    /// A [`Find`](crate::Find) that panics when consulted. All tests build on top of an empty root tree,
+    /// so no existing tree is ever loaded and this is never invoked.
+    struct PanicFind;
+
+    impl crate::Find for PanicFind {
+        fn try_find<'a>(
+            &self,
+            _id: &gix_hash::oid,
+            _buffer: &'a mut Vec<u8>,
+        ) -> Result<Option<crate::Data<'a>>, crate::find::Error> {
+            unreachable!("tests start from an empty root and never load existing trees")
+        }
+    }
+
+    fn blob() -> ObjectId {
+        ObjectId::empty_blob(gix_hash::Kind::Sha1)
+    }
+
+    #[test]
+    fn each_ancestor_visits_root_to_leaf_with_relative_paths() {
+        let find = PanicFind;
+        let mut editor = Editor::new(Tree::default(), &find);
+        let mut seen: Vec<Vec<u8>> = Vec::new();
+        editor
+            .upsert_with("a/b/c".split('/'), EntryKind::Blob, blob(), |path, _tree| {
+                seen.push(path.to_vec())
+            })
+            .expect("no lookup needed");
+        assert_eq!(
+            seen,
+            vec![b"".to_vec(), b"a".to_vec(), b"a/b".to_vec()],
+            "the callback fires once per ancestor tree, root first, with its relative path"
+        );
+    }
+
+    #[test]
+    fn remove_matching_then_write_prunes_emptied_directories() {
+        let find = PanicFind;
+        let mut editor = Editor::new(Tree::default(), &find);
+        editor.upsert("a/b/c".split('/'), EntryKind::Blob, blob()).unwrap();
+        editor.upsert("a/b/d".split('/'), EntryKind::Blob, blob()).unwrap();
+        editor.upsert("x".split('/'), EntryKind::Blob, blob()).unwrap();
+        editor
+            .remove_matching(|path, _kind| path.starts_with(b"a/"))
+            .unwrap();
+        let mut root = None;
+        editor
+            .write(|tree| {
+                root = Some(tree.entries.iter().map(|e| e.filename.to_vec()).collect::<Vec<_>>());
+                Ok::<_, std::convert::Infallible>(blob())
+            })
+            .unwrap();
+        assert_eq!(
+            root,
+            Some(vec![b"x".to_vec()]),
+            "the top-level `a` never matches `a/` itself but is pruned once its subtree is emptied"
+        );
+    }
+
+    #[test]
+    fn rename_records_provenance_and_invalidates_on_overwrite_and_remove() {
+        let find = PanicFind;
+        let mut editor = Editor::new(Tree::default(), &find);
+        editor.upsert("src/a".split('/'), EntryKind::Blob, blob()).unwrap();
+        editor.rename("src/a".split('/'), "dst/b".split('/')).unwrap();
+        assert_eq!(
+            editor.copy_source("dst/b".split('/')).map(|s| s.to_vec()),
+            Some(b"src/a".to_vec())
+        );
+
+        editor.upsert("dst/b".split('/'), EntryKind::Blob, blob()).unwrap();
+        assert_eq!(
+            editor.copy_source("dst/b".split('/')),
+            None,
+            "overwriting the destination drops its recorded provenance"
+        );
+
+        editor.rename("dst/b".split('/'), "dst/c".split('/')).unwrap();
+        assert_eq!(
+            editor.copy_source("dst/c".split('/')).map(|s| s.to_vec()),
+            Some(b"dst/b".to_vec())
+        );
+        editor.remove("dst/c".split('/')).unwrap();
+        assert_eq!(
+            editor.copy_source("dst/c".split('/')),
+            None,
+            "removing the destination drops its recorded provenance"
+        );
+    }
+
+    #[test]
+    fn rename_with_missing_source_clears_stale_destination_provenance() {
+        let find = PanicFind;
+        let mut editor = Editor::new(Tree::default(), &find);
+        editor.upsert("src/a".split('/'), EntryKind::Blob, blob()).unwrap();
+        editor.rename("src/a".split('/'), "dst/b".split('/')).unwrap();
+        assert_eq!(
+            editor.copy_source("dst/b".split('/')).map(|s| s.to_vec()),
+            Some(b"src/a".to_vec())
+        );
+
+        // The source no longer exists, so this move is a no-op - but it must still replace (here, clear)
+        // the provenance previously recorded for the destination.
+        editor.rename("nope".split('/'), "dst/b".split('/')).unwrap();
+        assert_eq!(
+            editor.copy_source("dst/b".split('/')),
+            None,
+            "a no-op rename still invalidates stale provenance for its destination"
+        );
+    }
+
+    #[test]
+    fn drain_copy_sources_yields_recorded_pairs() {
+        let find = PanicFind;
+        let mut editor = Editor::new(Tree::default(), &find);
+        editor.upsert("src/a".split('/'), EntryKind::Blob, blob()).unwrap();
+        editor.rename("src/a".split('/'), "dst/b".split('/')).unwrap();
+        let drained: Vec<_> = editor
+            .drain_copy_sources()
+            .map(|(dst, src)| (dst.to_vec(), src.to_vec()))
+            .collect();
+        assert_eq!(drained, vec![(b"dst/b".to_vec(), b"src/a".to_vec())]);
+        assert_eq!(
+            editor.copy_source("dst/b".split('/')),
+            None,
+            "draining empties the provenance map"
+        );
+    }
+
+    #[test]
+    fn write_round_trips_the_nested_node_structure() {
+        let find = PanicFind;
+        let mut editor = Editor::new(Tree::default(), &find);
+        editor.upsert("a/b/c".split('/'), EntryKind::Blob, blob()).unwrap();
+        editor.upsert("x".split('/'), EntryKind::Blob, blob()).unwrap();
+        let mut written: Vec<Vec<Vec<u8>>> = Vec::new();
+        editor
+            .write(|tree| {
+                written.push(tree.entries.iter().map(|e| e.filename.to_vec()).collect());
+                Ok::<_, std::convert::Infallible>(blob())
+            })
+            .unwrap();
+        assert_eq!(
+            written,
+            vec![
+                vec![b"c".to_vec()],
+                vec![b"b".to_vec()],
+                vec![b"a".to_vec(), b"x".to_vec()],
+            ],
+            "children are written before their parents and folded back into the enclosing tree"
+        );
+    }
 }